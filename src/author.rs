@@ -0,0 +1,201 @@
+//! Persisted, passphrase-encrypted author identity for the local node.
+//!
+//! The node signs every poem entry with an `iroh_docs::Author`. Without
+//! persistence a fresh author is minted on every launch, so peers can never
+//! recognize "the same writer" across restarts. This module loads the
+//! author key from `data_dir` if one was already written there, or creates
+//! and persists a new one on first run.
+//!
+//! The secret is never written to `author.key` in the clear: it's encrypted
+//! with a key derived from a user passphrase via Argon2id, and decryption
+//! doubles as passphrase verification (the AEAD tag fails to authenticate
+//! on a wrong guess), so a copy of that one file can't be used to sign
+//! poems without also knowing the passphrase.
+//!
+//! That said, this only protects `author.key` itself. `iroh_docs::Docs`
+//! keeps its own author store under `data_dir/docs` so it can sign CRDT
+//! entries without being handed the secret on every write, and both
+//! `new_author` and `import_author` below hand it the raw secret to persist
+//! there — unencrypted, by `iroh_docs`'s own design. So a stolen data
+//! directory still exposes the signing key via the docs store even with an
+//! encrypted `author.key` sitting next to it; the passphrase only raises
+//! the bar on the dedicated key file, not on the data directory as a whole.
+
+use std::path::{Path, PathBuf};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::{anyhow, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use dialoguer::{theme::SimpleTheme, Password};
+use iroh_docs::{protocol::Docs, Author, AuthorId};
+use rand::RngCore;
+
+use crate::BlobStore;
+
+const AUTHOR_FILE: &str = "author.key";
+const MAGIC: &[u8; 4] = b"P2AK";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const HEADER_LEN: usize = 4 + 4 + 4 + 4 + SALT_LEN + NONCE_LEN;
+
+/// Path where the node's encrypted signing key is stored inside `data_dir`.
+pub(crate) fn author_key_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(AUTHOR_FILE)
+}
+
+/// On-disk layout: `MAGIC | m_cost | t_cost | p_cost | salt | nonce | ciphertext`,
+/// all integers big-endian `u32`. The Argon2 params travel with the file so
+/// a future, more expensive default doesn't break existing keys.
+struct EncryptedAuthor {
+    params: Params,
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+impl EncryptedAuthor {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN + self.ciphertext.len());
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&self.params.m_cost().to_be_bytes());
+        buf.extend_from_slice(&self.params.t_cost().to_be_bytes());
+        buf.extend_from_slice(&self.params.p_cost().to_be_bytes());
+        buf.extend_from_slice(&self.salt);
+        buf.extend_from_slice(&self.nonce);
+        buf.extend_from_slice(&self.ciphertext);
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[0..4] != MAGIC {
+            return Err(anyhow!("not a recognized author key file"));
+        }
+        let m_cost = u32::from_be_bytes(bytes[4..8].try_into()?);
+        let t_cost = u32::from_be_bytes(bytes[8..12].try_into()?);
+        let p_cost = u32::from_be_bytes(bytes[12..16].try_into()?);
+        let salt = bytes[16..16 + SALT_LEN].try_into()?;
+        let nonce_start = 16 + SALT_LEN;
+        let nonce = bytes[nonce_start..nonce_start + NONCE_LEN].try_into()?;
+        let ciphertext = bytes[nonce_start + NONCE_LEN..].to_vec();
+
+        let params = Params::new(m_cost, t_cost, p_cost, Some(KEY_LEN))
+            .map_err(|err| anyhow!("invalid argon2 params in key file: {err}"))?;
+
+        Ok(Self {
+            params,
+            salt,
+            nonce,
+            ciphertext,
+        })
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: Params) -> Result<[u8; KEY_LEN]> {
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+    let mut key = [0u8; KEY_LEN];
+    argon2
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow!("key derivation failed: {err}"))?;
+    Ok(key)
+}
+
+fn encrypt(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher
+        .encrypt(Nonce::from_slice(nonce), plaintext)
+        .map_err(|_| anyhow!("encryption failed"))
+}
+
+fn decrypt(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    // A wrong passphrase derives a wrong key, which fails the AEAD tag here
+    // rather than producing garbage plaintext — that failure is how we
+    // detect and reject an incorrect passphrase.
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("incorrect passphrase"))
+}
+
+fn prompt_passphrase(prompt: &str) -> Result<String> {
+    Password::with_theme(&SimpleTheme)
+        .with_prompt(prompt)
+        .interact()
+        .context("reading passphrase")
+}
+
+/// Load the author persisted in `data_dir`, or create and persist a new one
+/// if this is the first launch against this data directory. Either way the
+/// user is prompted for a passphrase that protects `author.key` at rest; a
+/// wrong passphrase on an existing key re-prompts instead of panicking. See
+/// the module doc comment for why this does *not* protect the copy of the
+/// secret that `iroh_docs` keeps in its own author store.
+pub(crate) async fn load_or_create_author(
+    docs: &Docs<BlobStore>,
+    data_dir: &Path,
+) -> Result<AuthorId> {
+    let path = author_key_path(data_dir);
+
+    if path.exists() {
+        let bytes = std::fs::read(&path).context("reading persisted author key")?;
+        let encrypted = EncryptedAuthor::decode(&bytes)?;
+
+        let secret_bytes = loop {
+            let passphrase = prompt_passphrase("Passphrase for author key:")?;
+            let key = derive_key(&passphrase, &encrypted.salt, encrypted.params.clone())?;
+            match decrypt(&key, &encrypted.nonce, &encrypted.ciphertext) {
+                Ok(secret_bytes) => break secret_bytes,
+                Err(_) => {
+                    println!("Incorrect passphrase, please try again.");
+                }
+            }
+        };
+
+        let secret: [u8; KEY_LEN] = secret_bytes
+            .as_slice()
+            .try_into()
+            .context("decrypted author key has unexpected length")?;
+        let author = Author::from_bytes(&secret);
+        let id = author.id();
+        // `import_author` persists the raw secret into the docs store (see
+        // the module doc comment) — this is what lets `Docs` sign entries
+        // without us handing it the secret again on every write, at the
+        // cost of a second, unencrypted copy on disk.
+        docs.import_author(author).await?;
+        Ok(id)
+    } else {
+        // Same caveat as `import_author` above: `new_author` persists this
+        // author's raw secret into the docs store before we ever get a
+        // chance to encrypt anything.
+        let author = docs.new_author().await?;
+        let id = author.id();
+
+        let passphrase = prompt_passphrase("Set a passphrase to protect your author key:")?;
+
+        // Interactive Argon2id defaults: expensive enough to slow down
+        // offline guessing, cheap enough for a once-per-launch prompt.
+        let params = Params::new(19 * 1024, 2, 1, Some(KEY_LEN))
+            .map_err(|err| anyhow!("invalid argon2 params: {err}"))?;
+
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let key = derive_key(&passphrase, &salt, params.clone())?;
+
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = encrypt(&key, &nonce, &author.to_bytes())?;
+
+        let encrypted = EncryptedAuthor {
+            params,
+            salt,
+            nonce,
+            ciphertext,
+        };
+        std::fs::write(&path, encrypted.encode()).context("persisting encrypted author key")?;
+
+        Ok(id)
+    }
+}
@@ -1,33 +1,147 @@
+mod author;
+mod metrics;
+mod presence;
+mod registry;
+mod transfer;
+
 use chrono::Utc;
 use dialoguer::{theme::SimpleTheme, Input};
 use futures_lite::StreamExt;
-use iroh::{protocol::Router, Endpoint};
-use iroh_blobs::{store::Store as BlobStore, net_protocol::Blobs, ALPN as BLOBS_ALPN};
-use iroh_docs::{DocTicket, engine::LiveEvent, protocol::Docs, ALPN as DOCS_ALPN};
+use iroh::{protocol::Router, Endpoint, NodeAddr};
+use iroh_blobs::{net_protocol::Blobs, store::fs::Store as FsBlobStore, ALPN as BLOBS_ALPN};
+use iroh_docs::{
+    engine::LiveEvent,
+    protocol::Docs,
+    store::{Query, SortBy, SortDirection},
+    AuthorId, DocTicket, ALPN as DOCS_ALPN,
+};
 use iroh_gossip::{net::Gossip, ALPN as GOSSIP_ALPN};
-use std::{sync::Arc, str::FromStr};
+use registry::{Room, RoomRegistry};
+use std::{collections::HashSet, path::PathBuf, str::FromStr, sync::Arc};
+use tokio::sync::Mutex;
+use tracing::Instrument;
 use tracing_subscriber::{prelude::*, EnvFilter};
 use iroh_blobs::util::local_pool::LocalPool;
 
-// Set up logging for the application
-fn setup_logging() {
+/// The on-disk blob store backing `Docs`, used throughout instead of the
+/// in-memory store so poems and attachments survive restarts.
+pub(crate) type BlobStore = FsBlobStore;
+
+/// The doc key used for the control entry that stores a room's own ticket
+/// (see `handle_create`). Never a poem line, so it's filtered out of both
+/// history replay and the live loop.
+const TICKET_KEY: &str = "poem-ticket";
+
+/// Default data directory, relative to the current working directory, used
+/// when neither `--data-dir` nor `POEM_DATA_DIR` is set.
+const DEFAULT_DATA_DIR: &str = ".poem2poem";
+/// Default bind address for the Prometheus `/metrics` endpoint.
+const DEFAULT_METRICS_ADDR: &str = "127.0.0.1:9090";
+
+/// Resolve the data directory from `--data-dir <path>`, falling back to the
+/// `POEM_DATA_DIR` env var and then to [`DEFAULT_DATA_DIR`].
+fn data_dir() -> PathBuf {
+    let mut args = std::env::args();
+    while let Some(arg) = args.next() {
+        if arg == "--data-dir" {
+            if let Some(dir) = args.next() {
+                return PathBuf::from(dir);
+            }
+        }
+    }
+    if let Ok(dir) = std::env::var("POEM_DATA_DIR") {
+        return PathBuf::from(dir);
+    }
+    PathBuf::from(DEFAULT_DATA_DIR)
+}
+
+/// Resolve the metrics bind address from `--metrics-addr <addr>`, falling
+/// back to `POEM_METRICS_ADDR` and then [`DEFAULT_METRICS_ADDR`].
+fn metrics_addr() -> std::net::SocketAddr {
+    let mut args = std::env::args();
+    let raw = loop {
+        match args.next() {
+            Some(arg) if arg == "--metrics-addr" => break args.next(),
+            Some(_) => continue,
+            None => break std::env::var("POEM_METRICS_ADDR").ok(),
+        }
+    }
+    .unwrap_or_else(|| DEFAULT_METRICS_ADDR.to_string());
+
+    raw.parse().unwrap_or_else(|err| {
+        eprintln!("Invalid metrics address '{raw}' ({err}), using default");
+        DEFAULT_METRICS_ADDR.parse().expect("default metrics addr is valid")
+    })
+}
+
+/// `POEM_OTLP_ENDPOINT`, if set, enables OTLP span export to that collector
+/// endpoint alongside the stderr fmt layer.
+fn otlp_endpoint() -> Option<String> {
+    std::env::var("POEM_OTLP_ENDPOINT").ok()
+}
+
+// Set up logging for the application, plus optional OTLP trace export so
+// spans around `handle_create`, `handle_join`, and blob/doc operations can
+// be shipped to a collector.
+fn setup_logging(otlp_endpoint: Option<&str>) {
+    let otel_layer = otlp_endpoint.and_then(|endpoint| match build_otlp_layer(endpoint) {
+        Ok(layer) => Some(layer),
+        Err(err) => {
+            eprintln!("Failed to initialize OTLP export to {endpoint}: {err}");
+            None
+        }
+    });
+
     tracing_subscriber::registry()
         .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .with(EnvFilter::from_default_env())
+        .with(otel_layer)
         .try_init()
         .ok();
 }
 
+// Build the OTLP tracing layer, exporting batched spans to `endpoint` over
+// OTLP/gRPC.
+fn build_otlp_layer(
+    endpoint: &str,
+) -> anyhow::Result<impl tracing_subscriber::Layer<tracing_subscriber::Registry>> {
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::WithExportConfig;
+
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+            opentelemetry_sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+                "service.name",
+                "poem2poem",
+            )]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+    Ok(tracing_opentelemetry::layer().with_tracer(provider.tracer("poem2poem")))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    setup_logging();
+    setup_logging(otlp_endpoint().as_deref());
     println!("Poem Sharing P2P Node");
 
+    // Resolve and create the data directory that backs this node's state
+    let data_dir = data_dir();
+    std::fs::create_dir_all(&data_dir)?;
+
     // Create an iroh endpoint
     let endpoint = Endpoint::builder().discovery_n0().bind().await?;
 
-    // Create an in-memory blob store
-    let blobs = Blobs::memory().build(&endpoint);
+    // Create a filesystem-backed blob store rooted at the data directory
+    let blobs = Blobs::persistent(data_dir.join("blobs"))
+        .await?
+        .build(&endpoint);
 
     // Turn on the "rpc" feature if you need to create blobs and tags clients
     let blobs_client = blobs.client();
@@ -39,51 +153,88 @@ async fn main() -> Result<(), anyhow::Error> {
     // Build the gossip protocol
     let gossip = Gossip::builder().spawn(builder.endpoint().clone()).await?;
 
-    // Build the docs protocol
-    let docs = Docs::<BlobStore>::memory().spawn(&blobs, &gossip).await?;
+    // Build the docs protocol, persisted alongside the blob store
+    let docs = Docs::<BlobStore>::persistent(data_dir.join("docs"))
+        .spawn(&blobs, &gossip)
+        .await?;
+
+    // Build the direct bulk-transfer protocol, used for point-to-point
+    // sends that shouldn't bloat the replicated poem document
+    let transfer = transfer::TransferHandler::new(blobs_client.clone());
 
     // Setup router
     let router = builder
         .accept(BLOBS_ALPN, blobs.clone())
         .accept(GOSSIP_ALPN, gossip.clone())
         .accept(DOCS_ALPN, docs.clone())
+        .accept(transfer::TRANSFER_ALPN, transfer)
         .spawn()
         .await?;
 
+    // Load the persisted author, minting one on first run against this data dir
+    let author = author::load_or_create_author(&docs, &data_dir).await?;
+
+    // Set up Prometheus metrics and serve them on /metrics
+    let metrics = metrics::Metrics::new()?;
+    tokio::spawn(metrics::serve(metrics.clone(), metrics_addr()));
+    tokio::spawn(metrics::track_peers_connected(
+        metrics.clone(),
+        endpoint.clone(),
+    ));
+
     // Initialize the Iroh node
     let iroh = Iroh {
         _local_pool: Arc::new(LocalPool::default()),
         router,
+        endpoint,
         gossip: Arc::new(gossip),
         blobs: blobs_client.clone(),
         docs,
+        author,
+        rooms: Arc::new(Mutex::new(RoomRegistry::default())),
+        metrics,
     };
 
-    println!("Author: {}", iroh.docs.list_authors().await?.fmt_short());
+    println!("Author: {}", iroh.author.fmt_short());
 
     // Start the menu loop for user interaction
     tokio::spawn(menu_loop(iroh.clone())).await?;
     Ok(())
 }
 
-// Menu loop for user commands
+// Menu loop for user commands. A node can have several rooms open at once,
+// so besides `create`/`join` this also recognizes `list`/`switch <id>`/
+// `leave <id>`/`send <peer> <path>`; any other input is sent as a poem line
+// to whichever room is currently selected.
 async fn menu_loop(node: Iroh) {
     loop {
-        // Prompt user for a command
-        match Input::<String>::with_theme(&SimpleTheme)
-            .with_prompt("Command (create/join):")
+        let prompt = match node.rooms.lock().await.current() {
+            Some(room) => format!("[{}] (create/join/list/switch/leave, or type a line)", room.label),
+            None => "Command (create/join):".to_string(),
+        };
+
+        let input = Input::<String>::with_theme(&SimpleTheme)
+            .with_prompt(prompt)
             .interact_text()
-            .unwrap()
-            .as_str()
-        {
-            "create" => handle_create(&node).await, // Handle document creation
-            "join" => handle_join(&node).await,     // Handle joining a document
+            .unwrap();
+
+        let mut words = input.splitn(2, ' ');
+        match words.next().unwrap_or("") {
+            "create" => handle_create(&node).await,
+            "join" => handle_join(&node).await,
+            "list" => handle_list(&node).await,
+            "switch" => handle_switch(&node, words.next().unwrap_or("").trim()).await,
+            "leave" => handle_leave(&node, words.next().unwrap_or("").trim()).await,
+            "send" => handle_send(&node, words.next().unwrap_or("").trim()).await,
+            "" => {}
+            _ if node.rooms.lock().await.current().is_some() => handle_line(&node, input).await,
             _ => println!("Invalid command. Please enter 'create' or 'join'."),
         }
     }
 }
 
 // Handle creating a new document
+#[tracing::instrument(skip(node))]
 async fn handle_create(node: &Iroh) {
     // Create a new document
     let temp_doc = node.docs.new_author().await.unwrap();
@@ -101,29 +252,16 @@ async fn handle_create(node: &Iroh) {
     // Import the document for synchronization
     let poem_doc = node.docs.import(ticket.clone()).await.unwrap();
     poem_doc.set_bytes(
-        node.docs.list_authors().await.unwrap(),
-        "poem-ticket",
+        node.author,
+        TICKET_KEY,
         ticket.to_string(),
-    ).await.unwrap();
+    ).instrument(tracing::info_span!("set_bytes")).await.unwrap();
 
-    // Start the poem loop for real-time updates
-    tokio::spawn(poem_loop(poem_doc.clone(), node.clone()));
-    loop {
-        // Prompt user for poem input
-        let line = Input::with_theme(&SimpleTheme)
-            .with_prompt("Poem:")
-            .interact_text()
-            .unwrap();
-        let timestamp = Utc::now().timestamp_micros().to_string();
-        poem_doc.set_bytes(
-            node.docs.list_authors().await.unwrap(),
-            timestamp,
-            line,
-        ).await.unwrap();
-    }
+    join_room(node, poem_doc, &ticket).await;
 }
 
 // Handle joining an existing document
+#[tracing::instrument(skip(node))]
 async fn handle_join(node: &Iroh) {
     // Prompt user for a ticket to join
     let join_ticket: String = Input::<String>::with_theme(&SimpleTheme)
@@ -137,46 +275,283 @@ async fn handle_join(node: &Iroh) {
     let doc_ticket = DocTicket::from_str(&join_ticket).expect("Invalid ticket format");
 
     // Import the document using the ticket
-    let poem_doc = node.docs.import(doc_ticket).await.unwrap();
-    tokio::spawn(poem_loop(poem_doc.clone(), node.clone()));
-    loop {
-        // Prompt user for poem input
-        let line: String = Input::<String>::with_theme(&SimpleTheme)
-            .with_prompt("Poem:")
-            .interact_text()
-            .unwrap();
-        let timestamp = Utc::now().timestamp_micros().to_string();
-        poem_doc.set_bytes(
-            node.docs.list_authors().await.unwrap(),
-            timestamp,
-            line,
-        ).await.unwrap();
+    let poem_doc = node.docs.import(doc_ticket.clone()).await.unwrap();
+
+    join_room(node, poem_doc, &doc_ticket).await;
+}
+
+// Common room-setup behind both `create` and `join`: spawn the background
+// poem loop, best-effort join the presence topic, and register the room as
+// the currently selected one.
+async fn join_room(node: &Iroh, poem_doc: Docs<BlobStore>, ticket: &DocTicket) {
+    let namespace = poem_doc.id();
+    let label = namespace.fmt_short();
+
+    let poem_task = tokio::spawn(poem_loop(poem_doc.clone(), node.clone()));
+    let presence = spawn_presence(node, &poem_doc, ticket).await;
+
+    let room = Room::new(poem_doc, label.clone(), poem_task, presence);
+    node.rooms.lock().await.insert(namespace, room);
+    node.metrics.rooms_open.inc();
+
+    println!("Joined room {label}");
+}
+
+// Join the presence gossip topic for `poem_doc`, bootstrapping off the
+// node-ids embedded in `ticket`. Failures are logged and treated as
+// non-fatal: presence is a nice-to-have, not required to use the poem.
+async fn spawn_presence(
+    node: &Iroh,
+    poem_doc: &Docs<BlobStore>,
+    ticket: &DocTicket,
+) -> Option<presence::PresenceHandle> {
+    let author = match node.docs.export_author(node.author).await {
+        Ok(Some(author)) => author,
+        Ok(None) => {
+            eprintln!("Cannot join presence topic: author not found");
+            return None;
+        }
+        Err(err) => {
+            eprintln!("Cannot join presence topic: {err}");
+            return None;
+        }
+    };
+
+    let namespace = poem_doc.id();
+    let bootstrap = presence::bootstrap_peers(ticket);
+    match presence::spawn(
+        (*node.gossip).clone(),
+        author,
+        node.author.fmt_short(),
+        namespace,
+        bootstrap,
+    )
+    .await
+    {
+        Ok(handle) => Some(handle),
+        Err(err) => {
+            eprintln!("Cannot join presence topic: {err}");
+            None
+        }
+    }
+}
+
+// List every room this node currently has open, marking the selected one.
+async fn handle_list(node: &Iroh) {
+    let rooms = node.rooms.lock().await;
+    if rooms.is_empty() {
+        println!("No rooms open yet. Use 'create' or 'join'.");
+        return;
+    }
+    for (namespace, label, is_current) in rooms.list() {
+        let marker = if is_current { "*" } else { " " };
+        println!("{marker} {} ({label})", namespace.fmt_short());
+    }
+}
+
+// Switch the currently selected room to the one whose short id starts with
+// `id_prefix`.
+async fn handle_switch(node: &Iroh, id_prefix: &str) {
+    if id_prefix.is_empty() {
+        println!("Usage: switch <id>");
+        return;
+    }
+    match node.rooms.lock().await.switch(id_prefix) {
+        Some(label) => println!("Switched to {label}"),
+        None => println!("No open room matches '{id_prefix}'"),
+    }
+}
+
+// Leave (and stop background tasks for) the room whose short id starts with
+// `id_prefix`.
+async fn handle_leave(node: &Iroh, id_prefix: &str) {
+    if id_prefix.is_empty() {
+        println!("Usage: leave <id>");
+        return;
+    }
+    match node.rooms.lock().await.leave(id_prefix) {
+        Some(label) => {
+            node.metrics.rooms_open.dec();
+            println!("Left {label}");
+        }
+        None => println!("No open room matches '{id_prefix}'"),
+    }
+}
+
+// Hash a local file into blobs and hand it directly to `peer` over the
+// transfer ALPN. The bytes themselves bypass the poem document (see
+// `transfer`'s module doc), but the resulting hash is recorded as a line in
+// the currently selected room so every peer of that poem learns the
+// attachment exists and can fetch it with its own hash.
+async fn handle_send(node: &Iroh, args: &str) {
+    let mut parts = args.splitn(2, ' ');
+    let (Some(peer), Some(path)) = (parts.next(), parts.next()) else {
+        println!("Usage: send <peer-node-id> <path>");
+        return;
+    };
+
+    let node_id = match iroh::NodeId::from_str(peer) {
+        Ok(id) => id,
+        Err(err) => {
+            println!("Invalid peer id: {err}");
+            return;
+        }
+    };
+
+    let result = transfer::send_file(
+        &node.endpoint,
+        &node.blobs,
+        &node.metrics,
+        NodeAddr::from(node_id),
+        std::path::Path::new(path.trim()),
+    )
+    .await;
+
+    match result {
+        Ok(hash) => {
+            println!("Sent file, blob hash: {hash}");
+            let rooms = node.rooms.lock().await;
+            match rooms.current() {
+                Some(room) => {
+                    let timestamp = Utc::now().timestamp_micros().to_string();
+                    if let Err(err) = room
+                        .doc
+                        .set_bytes(node.author, timestamp, format!("[attachment] {hash}"))
+                        .await
+                    {
+                        eprintln!("Failed to record attachment in poem document: {err}");
+                    }
+                }
+                None => println!("No room selected; attachment hash not recorded in any poem."),
+            }
+        }
+        Err(err) => println!("Send failed: {err}"),
+    }
+}
+
+// Write a line of poem input to the currently selected room.
+async fn handle_line(node: &Iroh, line: String) {
+    let rooms = node.rooms.lock().await;
+    let Some(room) = rooms.current() else {
+        println!("No room selected. Use 'create' or 'join' first.");
+        return;
+    };
+    room.notify_typing();
+    let timestamp = Utc::now().timestamp_micros().to_string();
+    room.doc
+        .set_bytes(node.author, timestamp, line)
+        .instrument(tracing::info_span!("set_bytes"))
+        .await
+        .unwrap();
+}
+
+// Replay every entry already in the document, oldest first, so a peer who
+// joins an established poem sees the full history rather than only new
+// lines. Keys are the microsecond timestamps used in `set_bytes`, so sorting
+// by key reproduces write order.
+async fn replay_history(
+    poem_doc: &Docs<BlobStore>,
+    blobs: &BlobStore,
+    metrics: &metrics::Metrics,
+    seen: &mut HashSet<(AuthorId, Vec<u8>)>,
+) {
+    let query = Query::all().sort_by(SortBy::KeyAuthor, SortDirection::Asc);
+    let mut entries = match poem_doc.get_many(query).await {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("Failed to replay poem history: {err}");
+            return;
+        }
+    };
+
+    while let Some(Ok(entry)) = entries.next().await {
+        seen.insert((entry.author(), entry.key().to_vec()));
+        if entry.key() == TICKET_KEY.as_bytes() {
+            continue;
+        }
+        match blobs
+            .read_to_bytes(entry.content_hash())
+            .instrument(tracing::info_span!("read_to_bytes"))
+            .await
+        {
+            Ok(content) => {
+                metrics.blob_bytes_read.inc_by(content.len() as u64);
+                println!(
+                    "{}: {}",
+                    entry.author().fmt_short(),
+                    String::from_utf8_lossy(&content)
+                );
+            }
+            Err(err) => eprintln!("Failed to read history entry: {err}"),
+        }
     }
 }
 
 // Loop to handle real-time updates to the poem document
 async fn poem_loop(poem_doc: Docs<BlobStore>, iroh: Iroh) {
-    let mut sub = poem_doc.subscribe().await.unwrap();
     let blobs = iroh.blobs.clone();
+    let mut seen = HashSet::new();
+
+    // Catch up on everything written before we subscribed
+    replay_history(&poem_doc, &blobs, &iroh.metrics, &mut seen).await;
+
+    let mut sub = poem_doc.subscribe().await.unwrap();
+
+    while let Ok(Some(event)) = sub.try_next().await {
+        let (entry, origin) = match event {
+            LiveEvent::InsertRemote { entry, .. } => (entry, "remote"),
+            LiveEvent::InsertLocal { entry } => (entry, "local"),
+            _ => continue,
+        };
+        // The author who wrote the entry, not (for `InsertRemote`) the peer
+        // that happened to relay it to us — `replay_history` also keys on
+        // `entry.author()`, so this has to match for the dedupe below to
+        // actually catch the history/live seam.
+        let from = entry.author();
+
+        // Dedupe against entries already printed during history replay, so
+        // the seam between history and live events shows no duplicates.
+        // Keyed on (author, key): two authors can legitimately write the
+        // same microsecond timestamp key without colliding here.
+        if !seen.insert((from, entry.key().to_vec())) {
+            continue;
+        }
+
+        if entry.key() == TICKET_KEY.as_bytes() {
+            continue;
+        }
+
+        // Only count entries once they've survived both filters above, so
+        // re-delivered/seam duplicates and the ticket control entry don't
+        // inflate this counter.
+        iroh.metrics.record_entry_inserted(origin);
 
-    while let Ok(Some(LiveEvent::InsertRemote { from, entry, .. })) = sub.try_next().await {
-        let mut message_content = blobs.read_to_bytes(entry.content_hash()).await;
+        let read_span = tracing::info_span!("read_to_bytes");
+        let mut message_content = blobs
+            .read_to_bytes(entry.content_hash())
+            .instrument(read_span.clone())
+            .await;
 
         // Retry up to 3 times if initial read fails
         for _ in 0..3 {
             if message_content.is_ok() {
                 break;
             }
+            iroh.metrics.blob_read_retries.inc();
             tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-            message_content = blobs.read_to_bytes(entry.content_hash()).await;
+            message_content = blobs
+                .read_to_bytes(entry.content_hash())
+                .instrument(read_span.clone())
+                .await;
         }
 
         // Print message if we successfully got the content
         if let Ok(content) = message_content {
+            iroh.metrics.blob_bytes_read.inc_by(content.len() as u64);
             println!(
                 "{}: {}",
                 from.fmt_short(),
-                String::from_utf8(content.into()).unwrap()
+                String::from_utf8_lossy(&content)
             );
         }
     }
@@ -187,7 +562,13 @@ async fn poem_loop(poem_doc: Docs<BlobStore>, iroh: Iroh) {
 pub(crate) struct Iroh {
     _local_pool: Arc<LocalPool>,
     router: Router,
+    pub(crate) endpoint: Endpoint,
     pub(crate) gossip: Arc<Gossip>,
     pub(crate) blobs: BlobStore,
     pub(crate) docs: Docs<BlobStore>,
+    /// The node's persisted signing identity, reused across restarts.
+    pub(crate) author: AuthorId,
+    /// Rooms (poem documents) this node currently has open.
+    pub(crate) rooms: Arc<Mutex<RoomRegistry>>,
+    pub(crate) metrics: metrics::Metrics,
 }
\ No newline at end of file
@@ -0,0 +1,139 @@
+//! Observability: Prometheus metrics.
+//!
+//! `setup_logging` used to wire nothing but a stderr fmt layer, and the
+//! blob-read retry loop in `poem_loop` silently swallowed failures. This
+//! module adds a `prometheus::Registry` with counters/gauges for the things
+//! that matter operationally (open rooms, connected peers, entries
+//! inserted, blob bytes read, blob-read retries) and serves them on a small
+//! HTTP `/metrics` endpoint. The optional OTLP span export is wired up
+//! separately, in `setup_logging` in `main.rs`.
+
+use std::{net::SocketAddr, time::Duration};
+
+use anyhow::Result;
+use iroh::{endpoint::ConnectionType, Endpoint};
+use prometheus::{Encoder, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// How often to resample `peers_connected` from the endpoint's remote-node
+/// table. Connections aren't events we get pushed, so we poll instead.
+const PEER_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Metrics for one running node, all registered against a single
+/// `prometheus::Registry` so they show up together on `/metrics`.
+#[derive(Clone)]
+pub(crate) struct Metrics {
+    registry: Registry,
+    pub(crate) rooms_open: IntGauge,
+    pub(crate) peers_connected: IntGauge,
+    entries_inserted: IntCounterVec,
+    pub(crate) blob_bytes_read: IntCounter,
+    pub(crate) blob_read_retries: IntCounter,
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Result<Self> {
+        let registry = Registry::new();
+
+        let rooms_open = IntGauge::new("poem2poem_rooms_open", "Poem rooms currently open")?;
+        let peers_connected =
+            IntGauge::new("poem2poem_peers_connected", "Peers currently connected")?;
+        let entries_inserted = IntCounterVec::new(
+            Opts::new("poem2poem_entries_inserted_total", "Doc entries inserted"),
+            &["origin"],
+        )?;
+        let blob_bytes_read = IntCounter::new(
+            "poem2poem_blob_bytes_read_total",
+            "Bytes read from the blob store",
+        )?;
+        let blob_read_retries = IntCounter::new(
+            "poem2poem_blob_read_retries_total",
+            "Blob read retries after an initial failure",
+        )?;
+
+        registry.register(Box::new(rooms_open.clone()))?;
+        registry.register(Box::new(peers_connected.clone()))?;
+        registry.register(Box::new(entries_inserted.clone()))?;
+        registry.register(Box::new(blob_bytes_read.clone()))?;
+        registry.register(Box::new(blob_read_retries.clone()))?;
+
+        Ok(Self {
+            registry,
+            rooms_open,
+            peers_connected,
+            entries_inserted,
+            blob_bytes_read,
+            blob_read_retries,
+        })
+    }
+
+    pub(crate) fn record_entry_inserted(&self, origin: &str) {
+        self.entries_inserted.with_label_values(&[origin]).inc();
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        // Only fails on a malformed metric family, which `register` above
+        // would already have rejected, so there's nothing useful to do with
+        // the error here but drop it.
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buf);
+        buf
+    }
+}
+
+/// Periodically resample `peers_connected` from `endpoint`'s remote-node
+/// table. `iroh::Endpoint` doesn't push connect/disconnect events, so this
+/// polls `remote_info_iter` and counts nodes that currently have a live
+/// connection path (direct, relay, or mixed) rather than `None`.
+pub(crate) async fn track_peers_connected(metrics: Metrics, endpoint: Endpoint) {
+    let mut ticker = tokio::time::interval(PEER_SAMPLE_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let connected = endpoint
+            .remote_info_iter()
+            .filter(|info| !matches!(info.conn_type, ConnectionType::None))
+            .count();
+        metrics.peers_connected.set(connected as i64);
+    }
+}
+
+/// Serve `/metrics` on `addr` until the process exits. Binding failures are
+/// logged and non-fatal: metrics are an operability aid, not required for
+/// the node to function.
+pub(crate) async fn serve(metrics: Metrics, addr: SocketAddr) {
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Failed to bind metrics endpoint on {addr}: {err}");
+            return;
+        }
+    };
+    println!("Serving metrics on http://{addr}/metrics");
+
+    loop {
+        let Ok((mut socket, _)) = listener.accept().await else {
+            continue;
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let mut discard = [0u8; 1024];
+            // We only ever serve one response, so the request itself (path,
+            // headers) is irrelevant; just drain it before replying.
+            let _ = socket.read(&mut discard).await;
+
+            let body = metrics.encode();
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            let _ = socket.write_all(header.as_bytes()).await;
+            let _ = socket.write_all(&body).await;
+        });
+    }
+}
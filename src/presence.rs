@@ -0,0 +1,259 @@
+//! Ephemeral presence over gossip.
+//!
+//! `gossip` is spawned and accepted by the router but nothing ever used it:
+//! every message so far has gone through persisted doc entries. This module
+//! gives it a job that doc entries are a poor fit for — "who's here right
+//! now" signals that should never become part of the permanent poem log.
+//!
+//! One gossip topic per poem, derived from the document's namespace id, so
+//! every peer of that poem lands on the same channel without extra
+//! coordination. Messages are small, author-signed, and printed on arrival;
+//! nothing here is written to the document.
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{anyhow, Result};
+use futures_lite::StreamExt;
+use iroh::NodeId;
+use iroh_docs::{Author, AuthorId, DocTicket, NamespaceId};
+use iroh_gossip::{
+    net::{Event as NetEvent, Gossip, GossipEvent},
+    proto::TopicId,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// How long a "typing" announcement should be considered current before a
+/// peer's UI treats it as stale.
+pub(crate) const TYPING_TTL: Duration = Duration::from_secs(5);
+/// How often we broadcast a heartbeat on a poem's presence topic.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A presence signal broadcast on a poem's gossip topic. Transient by
+/// design: receivers print these and move on, nothing is persisted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PresenceEvent {
+    Joined { label: String },
+    Left,
+    Typing,
+    Heartbeat,
+}
+
+impl PresenceEvent {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            PresenceEvent::Joined { label } => format!("JOIN {label}").into_bytes(),
+            PresenceEvent::Left => b"LEAVE".to_vec(),
+            PresenceEvent::Typing => b"TYPING".to_vec(),
+            PresenceEvent::Heartbeat => b"HEARTBEAT".to_vec(),
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(bytes).ok()?;
+        let (tag, rest) = text.split_once(' ').unwrap_or((text, ""));
+        match tag {
+            "JOIN" => Some(PresenceEvent::Joined {
+                label: rest.to_string(),
+            }),
+            "LEAVE" => Some(PresenceEvent::Left),
+            "TYPING" => Some(PresenceEvent::Typing),
+            "HEARTBEAT" => Some(PresenceEvent::Heartbeat),
+            _ => None,
+        }
+    }
+}
+
+/// One presence message on the wire: the event, which author sent it, and a
+/// signature over the encoded event so a peer can at least attribute who is
+/// announcing what.
+struct SignedPresence {
+    author: AuthorId,
+    signature: [u8; 64],
+    event: PresenceEvent,
+}
+
+const AUTHOR_LEN: usize = 32;
+const SIG_LEN: usize = 64;
+
+impl SignedPresence {
+    fn sign(author: &Author, event: PresenceEvent) -> Self {
+        let payload = event.encode();
+        let signature = author.sign(&payload).to_bytes();
+        Self {
+            author: author.id(),
+            signature,
+            event,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(AUTHOR_LEN + SIG_LEN + 16);
+        buf.extend_from_slice(self.author.as_bytes());
+        buf.extend_from_slice(&self.signature);
+        buf.extend_from_slice(&self.event.encode());
+        buf
+    }
+
+    fn decode_and_verify(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < AUTHOR_LEN + SIG_LEN {
+            return Err(anyhow!("presence message too short"));
+        }
+        let author = AuthorId::from(
+            <[u8; AUTHOR_LEN]>::try_from(&bytes[..AUTHOR_LEN])
+                .map_err(|_| anyhow!("malformed author id"))?,
+        );
+        let signature = <[u8; SIG_LEN]>::try_from(&bytes[AUTHOR_LEN..AUTHOR_LEN + SIG_LEN])
+            .map_err(|_| anyhow!("malformed signature"))?;
+        let event = PresenceEvent::decode(&bytes[AUTHOR_LEN + SIG_LEN..])
+            .ok_or_else(|| anyhow!("malformed presence event"))?;
+
+        author
+            .verify(&event.encode(), &signature)
+            .map_err(|_| anyhow!("presence message failed signature check"))?;
+
+        Ok(Self {
+            author,
+            signature,
+            event,
+        })
+    }
+}
+
+/// Derive a poem's presence topic from its document namespace, so every
+/// peer of that document computes the same gossip `TopicId` independently.
+pub(crate) fn topic_for_namespace(namespace: &NamespaceId) -> TopicId {
+    TopicId::from_bytes(*namespace.as_bytes())
+}
+
+/// Node ids embedded in a ticket, used to bootstrap the gossip swarm for a
+/// poem's presence topic immediately after `import`.
+pub(crate) fn bootstrap_peers(ticket: &DocTicket) -> Vec<NodeId> {
+    ticket.nodes.iter().map(|addr| addr.node_id).collect()
+}
+
+/// A joined presence topic: the sender the caller uses to queue outgoing
+/// events, plus the two background tasks that keep the topic alive. The
+/// outgoing task exits on its own once `tx` is dropped, but the incoming
+/// print loop has no reason to ever stop by itself, so both handles are
+/// kept here and [`PresenceHandle::shutdown`] drops `tx` and aborts the
+/// incoming task rather than leaving it to run past the room it belongs to.
+#[derive(Debug)]
+pub(crate) struct PresenceHandle {
+    pub(crate) tx: mpsc::Sender<PresenceEvent>,
+    outgoing_task: JoinHandle<()>,
+    incoming_task: JoinHandle<()>,
+}
+
+impl PresenceHandle {
+    /// Announce our departure and tear down both background tasks (the
+    /// outgoing one stops by itself after sending `Left`). Best
+    /// effort: a room is leaving either way, so a failed `Left` broadcast
+    /// isn't worth surfacing.
+    pub(crate) fn shutdown(self) {
+        let _ = self.tx.try_send(PresenceEvent::Left);
+        // Dropping `tx` lets the outgoing task broadcast the queued `Left`
+        // and then exit on its own; aborting it would discard that message.
+        drop(self.tx);
+        drop(self.outgoing_task);
+        self.incoming_task.abort();
+    }
+}
+
+/// Join the presence topic for one poem and spawn its background tasks:
+/// a join announcement, periodic heartbeats, forwarding of locally queued
+/// events (typing/leave), and printing of whatever peers broadcast. Returns
+/// a handle bundling the sender the caller uses to queue outgoing events
+/// (e.g. a typing tick for each line entered) with both tasks' `JoinHandle`s.
+pub(crate) async fn spawn(
+    gossip: Gossip,
+    author: Author,
+    label: String,
+    namespace: NamespaceId,
+    bootstrap: Vec<NodeId>,
+) -> Result<PresenceHandle> {
+    let topic = topic_for_namespace(&namespace);
+    let (sender, mut receiver) = gossip.subscribe(topic, bootstrap).await?.split();
+
+    let (tx, mut rx) = mpsc::channel::<PresenceEvent>(16);
+
+    // Announce ourselves as soon as we've joined the topic
+    let hello = SignedPresence::sign(&author, PresenceEvent::Joined { label });
+    sender.broadcast(hello.encode().into()).await?;
+
+    // Outgoing: periodic heartbeat plus whatever the caller queues
+    let outgoing_task = tokio::spawn({
+        let sender = sender.clone();
+        async move {
+            let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = heartbeat.tick() => {
+                        let msg = SignedPresence::sign(&author, PresenceEvent::Heartbeat);
+                        let _ = sender.broadcast(msg.encode().into()).await;
+                    }
+                    event = rx.recv() => {
+                        let Some(event) = event else { break };
+                        let msg = SignedPresence::sign(&author, event);
+                        let _ = sender.broadcast(msg.encode().into()).await;
+                    }
+                }
+            }
+        }
+    });
+
+    // Incoming: verify and print whatever peers broadcast. `Typing` is
+    // deliberately noisier than the other events (sent once per line typed
+    // rather than once per action), so track when each author's indicator
+    // should go stale (every tick pushes that out by `TYPING_TTL`) and only
+    // print it again once `TYPING_TTL` has passed since that author's last
+    // tick, instead of echoing every single tick.
+    let incoming_task = tokio::spawn(async move {
+        let mut typing_until: HashMap<AuthorId, tokio::time::Instant> = HashMap::new();
+        loop {
+            tokio::select! {
+                event = receiver.next() => {
+                    let Some(Ok(event)) = event else { break };
+                    let NetEvent::Gossip(GossipEvent::Received(message)) = event else {
+                        continue;
+                    };
+                    match SignedPresence::decode_and_verify(&message.content) {
+                        Ok(presence) => {
+                            if presence.event == PresenceEvent::Typing {
+                                let now = tokio::time::Instant::now();
+                                let stale = typing_until
+                                    .get(&presence.author)
+                                    .map_or(true, |until| now >= *until);
+                                typing_until.insert(presence.author, now + TYPING_TTL);
+                                if !stale {
+                                    continue;
+                                }
+                            }
+                            print_presence(&presence);
+                        }
+                        Err(err) => tracing::debug!(%err, "dropping malformed presence message"),
+                    }
+                }
+                _ = tokio::time::sleep(TYPING_TTL) => {
+                    let now = tokio::time::Instant::now();
+                    typing_until.retain(|_, until| *until > now);
+                }
+            }
+        }
+    });
+
+    Ok(PresenceHandle {
+        tx,
+        outgoing_task,
+        incoming_task,
+    })
+}
+
+fn print_presence(presence: &SignedPresence) {
+    let who = presence.author.fmt_short();
+    match &presence.event {
+        PresenceEvent::Joined { label } => println!("* {who} joined ({label})"),
+        PresenceEvent::Left => println!("* {who} left"),
+        PresenceEvent::Typing => println!("* {who} is typing..."),
+        PresenceEvent::Heartbeat => tracing::trace!(%who, "presence heartbeat"),
+    }
+}
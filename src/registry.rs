@@ -0,0 +1,120 @@
+//! Registry of concurrently open poem rooms.
+//!
+//! Previously the menu loop could only ever drive one document: entering
+//! `create` or `join` parked the whole process in an inner prompt loop tied
+//! to that single `poem_doc`. `RoomRegistry` instead tracks every room a
+//! node has joined, each with its own background `poem_loop` task, so
+//! `list`/`switch`/`leave` can move the prompt between rooms without
+//! dropping any of them.
+
+use std::collections::HashMap;
+
+use iroh_docs::{protocol::Docs, NamespaceId};
+use tokio::task::JoinHandle;
+
+use crate::{
+    presence::{PresenceEvent, PresenceHandle},
+    BlobStore,
+};
+
+/// One open room: its document handle, the background task that replays
+/// history and prints live updates, and the presence handle for that
+/// room's gossip topic (absent if presence failed to join).
+#[derive(Debug)]
+pub(crate) struct Room {
+    pub(crate) doc: Docs<BlobStore>,
+    pub(crate) label: String,
+    poem_task: JoinHandle<()>,
+    presence: Option<PresenceHandle>,
+}
+
+impl Room {
+    pub(crate) fn new(
+        doc: Docs<BlobStore>,
+        label: String,
+        poem_task: JoinHandle<()>,
+        presence: Option<PresenceHandle>,
+    ) -> Self {
+        Self {
+            doc,
+            label,
+            poem_task,
+            presence,
+        }
+    }
+
+    /// Best-effort typing tick; presence is a nice-to-have so failures are
+    /// swallowed rather than surfaced to the user.
+    pub(crate) fn notify_typing(&self) {
+        if let Some(presence) = &self.presence {
+            let _ = presence.tx.try_send(PresenceEvent::Typing);
+        }
+    }
+
+    fn shutdown(self) {
+        self.poem_task.abort();
+        if let Some(presence) = self.presence {
+            presence.shutdown();
+        }
+    }
+}
+
+/// Rooms this node currently participates in, plus which one input is
+/// routed to.
+#[derive(Debug, Default)]
+pub(crate) struct RoomRegistry {
+    rooms: HashMap<NamespaceId, Room>,
+    current: Option<NamespaceId>,
+}
+
+impl RoomRegistry {
+    /// Add a newly created/joined room and make it the active one.
+    pub(crate) fn insert(&mut self, namespace: NamespaceId, room: Room) {
+        self.rooms.insert(namespace, room);
+        self.current = Some(namespace);
+    }
+
+    pub(crate) fn current(&self) -> Option<&Room> {
+        self.current.and_then(|id| self.rooms.get(&id))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rooms.is_empty()
+    }
+
+    /// Rooms as `(namespace, label, is_current)`, for the `list` command.
+    pub(crate) fn list(&self) -> Vec<(NamespaceId, &str, bool)> {
+        self.rooms
+            .iter()
+            .map(|(id, room)| (*id, room.label.as_str(), Some(*id) == self.current))
+            .collect()
+    }
+
+    /// Switch the active room to whichever namespace's short id starts with
+    /// `id_prefix`. Returns the matched room's label, or `None` if no room
+    /// matched.
+    pub(crate) fn switch(&mut self, id_prefix: &str) -> Option<&str> {
+        let namespace = *self
+            .rooms
+            .keys()
+            .find(|id| id.to_string().starts_with(id_prefix))?;
+        self.current = Some(namespace);
+        self.rooms.get(&namespace).map(|room| room.label.as_str())
+    }
+
+    /// Remove and shut down whichever room's short id starts with
+    /// `id_prefix`, returning its label.
+    pub(crate) fn leave(&mut self, id_prefix: &str) -> Option<String> {
+        let namespace = *self
+            .rooms
+            .keys()
+            .find(|id| id.to_string().starts_with(id_prefix))?;
+        let room = self.rooms.remove(&namespace)?;
+        let label = room.label.clone();
+        if self.current == Some(namespace) {
+            self.current = self.rooms.keys().next().copied();
+        }
+        room.shutdown();
+        Some(label)
+    }
+}
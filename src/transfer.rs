@@ -0,0 +1,151 @@
+//! Direct peer-to-peer bulk transfer over a dedicated QUIC ALPN.
+//!
+//! Doc entries are a good fit for line-by-line text but a poor one for
+//! large payloads (an image, an audio recitation, a long manuscript):
+//! routing those through the CRDT sync path would replicate them to every
+//! peer of the document whether they want the file or not. This module
+//! registers a second ALPN and speaks a tiny push protocol directly
+//! between two nodes over one bidirectional QUIC stream: the sender
+//! announces a blob hash and size, then writes the bytes itself; the
+//! receiver reads exactly that many bytes and imports them into its own
+//! local blob store. Only the resulting hash needs to be written into the
+//! poem document.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use iroh::{
+    endpoint::Connection,
+    protocol::{AcceptError, ProtocolHandler},
+    Endpoint, NodeAddr,
+};
+use iroh_blobs::Hash;
+use n0_future::boxed::BoxFuture;
+use tokio::io::AsyncReadExt;
+
+use crate::{metrics::Metrics, BlobStore};
+
+/// ALPN for the direct transfer protocol, separate from [`iroh_blobs::ALPN`]
+/// so it can be dialed point-to-point without going through doc sync.
+pub(crate) const TRANSFER_ALPN: &[u8] = b"poem2poem/transfer/0";
+
+const HEADER_LEN: usize = 32 + 8;
+
+/// Header the sender writes before the raw bytes: which blob this is and
+/// how many bytes to expect, so the receiver knows when to stop reading.
+struct TransferHeader {
+    hash: Hash,
+    size: u64,
+}
+
+impl TransferHeader {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN);
+        buf.extend_from_slice(self.hash.as_bytes());
+        buf.extend_from_slice(&self.size.to_be_bytes());
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != HEADER_LEN {
+            bail!("malformed transfer header");
+        }
+        let hash = Hash::from_bytes(bytes[0..32].try_into()?);
+        let size = u64::from_be_bytes(bytes[32..40].try_into()?);
+        Ok(Self { hash, size })
+    }
+}
+
+/// Receiving side of the transfer protocol: read one header and its
+/// announced payload per connection, then import the bytes into the local
+/// blob store.
+#[derive(Debug, Clone)]
+pub(crate) struct TransferHandler {
+    blobs: BlobStore,
+}
+
+impl TransferHandler {
+    pub(crate) fn new(blobs: BlobStore) -> Self {
+        Self { blobs }
+    }
+}
+
+impl ProtocolHandler for TransferHandler {
+    fn accept(&self, connection: Connection) -> BoxFuture<Result<(), AcceptError>> {
+        let blobs = self.blobs.clone();
+        Box::pin(async move {
+            let (mut send, mut recv) = connection.accept_bi().await?;
+
+            // The sender never FINs between the header and the body, so
+            // `read_to_end` here would block reading the header until the
+            // whole stream closes and then fail once the total exceeds
+            // `HEADER_LEN`. Read the fixed-size header with `read_exact`
+            // instead, and only use `read_to_end` for the body, whose end
+            // really is marked by the stream's FIN.
+            let mut header_bytes = [0u8; HEADER_LEN];
+            recv.read_exact(&mut header_bytes)
+                .await
+                .map_err(AcceptError::from_err)?;
+            let header = TransferHeader::decode(&header_bytes).map_err(AcceptError::from_err)?;
+
+            let content = recv.read_to_end(header.size as usize).await?;
+            let added = blobs
+                .add_bytes(content)
+                .await
+                .map_err(AcceptError::from_err)?;
+            if added.hash != header.hash {
+                return Err(AcceptError::from_err(anyhow::anyhow!(
+                    "received bytes hash to {}, sender announced {}",
+                    added.hash,
+                    header.hash
+                )));
+            }
+
+            send.finish()?;
+            connection.closed().await;
+            Ok(())
+        })
+    }
+}
+
+/// Sending side of the transfer protocol: hash `path` into the local blob
+/// store, open a direct connection to `addr`, and push the bytes over the
+/// transfer ALPN so `addr` imports them into its own blob store. Returns
+/// the resulting hash, which is all that needs to be recorded in the poem
+/// document.
+pub(crate) async fn send_file(
+    endpoint: &Endpoint,
+    blobs: &BlobStore,
+    metrics: &Metrics,
+    addr: NodeAddr,
+    path: &Path,
+) -> Result<Hash> {
+    let added = blobs
+        .add_path(path.to_path_buf())
+        .await
+        .context("hashing file into blobs")?;
+    let content = blobs
+        .read_to_bytes(added.hash)
+        .await
+        .context("reading hashed file back out of blobs")?;
+    metrics.blob_bytes_read.inc_by(content.len() as u64);
+
+    let connection = endpoint.connect(addr, TRANSFER_ALPN).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+
+    let header = TransferHeader {
+        hash: added.hash,
+        size: added.size,
+    };
+    send.write_all(&header.encode()).await?;
+    send.write_all(&content).await?;
+    send.finish()?;
+
+    // Wait for the receiver to finish importing and close its side, so we
+    // don't report success before the peer has actually stored the bytes.
+    recv.read_to_end(0)
+        .await
+        .context("peer did not confirm the transfer")?;
+
+    Ok(added.hash)
+}